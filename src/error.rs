@@ -0,0 +1,61 @@
+//! Crate-level error type covering startup misconfiguration and probe
+//! failures. Configuration errors are returned from `main`; a ping failure
+//! is reported and folded into a failed sample, and
+//! [`Error::is_permission_denied`] exists because one kind of probe
+//! failure — missing raw-socket privilege — is never going to clear up on
+//! its own and is worth treating differently from "host didn't answer".
+
+use std::net::{AddrParseError, IpAddr};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0} must be set in .env")]
+    MissingEnvVar(&'static str),
+
+    #[error("invalid IP address {value:?} in IP_ADDRESSES: {source}")]
+    InvalidIpAddress {
+        value: String,
+        #[source]
+        source: AddrParseError,
+    },
+
+    #[error("invalid {name}: {source}")]
+    InvalidConfigValue {
+        name: &'static str,
+        #[source]
+        source: std::num::ParseIntError,
+    },
+
+    #[error("ping to {ip_address} failed: {detail}")]
+    Ping { ip_address: IpAddr, detail: String },
+}
+
+impl Error {
+    /// True when this looks like a raw-socket permission failure (missing
+    /// `CAP_NET_RAW` / administrator privilege) rather than a simple "host
+    /// unreachable" response at this probe. Used to decide whether to keep
+    /// trying smaller MTU sizes or bail out immediately.
+    ///
+    /// `detail` is a `{:?}` of whatever `ping_rs` returned, since its error
+    /// type's exact shape (and whether it wraps a `std::io::Error`) isn't
+    /// guaranteed across platforms. This is a best-effort string match
+    /// across the wordings a permission failure tends to show up as —
+    /// `io::ErrorKind::PermissionDenied`'s own Debug output, a raw EACCES,
+    /// or a bare OS error code 13 — rather than a guaranteed detection.
+    pub fn is_permission_denied(&self) -> bool {
+        match self {
+            Error::Ping { detail, .. } => {
+                let lower = detail.to_lowercase();
+                lower.contains("permission")
+                    || lower.contains("access")
+                    || lower.contains("denied")
+                    || lower.contains("eacces")
+                    || lower.contains("os error 13")
+            }
+            Error::MissingEnvVar(_)
+            | Error::InvalidIpAddress { .. }
+            | Error::InvalidConfigValue { .. } => false,
+        }
+    }
+}
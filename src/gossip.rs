@@ -0,0 +1,287 @@
+//! Cross-node gossip so several probe agents can share a single view of
+//! reachability collected from multiple vantage points, and the HTML report
+//! can plot all of them on one chart instead of just this agent's own.
+//!
+//! Each `(agent_id, IpAddr)` key keeps a bounded history, not just the
+//! latest reading: every merge either extends that history with a new
+//! finalized minute or reconciles a repeat of the same minute, so a peer
+//! can be gossiped to any number of times, in any order, without
+//! coordination, the same way the local probe loop's own per-minute
+//! bookkeeping works.
+
+use crate::model::{PingResult, TimedResult};
+use chrono::{DateTime, Local};
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::Instant;
+use tokio::sync::Mutex;
+
+pub type AgentId = String;
+pub type GossipKey = (AgentId, IpAddr);
+
+/// A single `(agent, ip)` observation gossiped across the mesh: the
+/// `TimedResult` to render, plus how many successful probe samples (the
+/// total of its latency histogram) the *originating* agent had folded into
+/// it by the time it was sent. Failed probes still change the result's MTU
+/// and latency but are not counted.
+///
+/// `sample_count` is what makes reconciling two reports for the same
+/// minute order-independent when their counts differ: an agent only ever
+/// accumulates more samples as a minute progresses, never fewer, so the
+/// report with the higher count strictly supersedes the other regardless
+/// of which one a given node happened to receive first. Of two reports
+/// with equal counts, [`GossipStore::merge`] keeps the one that arrived last.
+#[derive(Clone)]
+pub struct GossipEntry {
+    pub result: TimedResult,
+    pub sample_count: u64,
+}
+
+/// Retained history for every `(agent, ip)` pair seen anywhere in the mesh.
+#[derive(Default)]
+pub struct GossipStore {
+    entries: Mutex<HashMap<GossipKey, VecDeque<GossipEntry>>>,
+}
+
+impl GossipStore {
+    /// Merges a single `(agent, ip)` entry into that key's history. An
+    /// entry for a new minute is appended; one repeating the most recent
+    /// minute replaces it only if it has a `sample_count` at least as high
+    /// (a later, more-informed report for a minute still in progress);
+    /// anything older than the most recent minute already held is a stale
+    /// retransmit and is dropped.
+    pub async fn merge(&self, key: GossipKey, entry: GossipEntry) {
+        let mut entries = self.entries.lock().await;
+        let history = entries.entry(key).or_default();
+        match history.back_mut() {
+            Some(last) if last.result.0 == entry.result.0 => {
+                if entry.sample_count >= last.sample_count {
+                    *last = entry;
+                }
+            }
+            Some(last) if last.result.0 > entry.result.0 => {}
+            _ => history.push_back(entry),
+        }
+    }
+
+    /// The latest known report for every key, for gossiping to a peer.
+    pub async fn snapshot(&self) -> Vec<(GossipKey, GossipEntry)> {
+        self.entries
+            .lock()
+            .await
+            .iter()
+            .filter_map(|(key, history)| history.back().map(|entry| (key.clone(), entry.clone())))
+            .collect()
+    }
+
+    /// The full retained history for every key, for rendering a chart with
+    /// one series per vantage point.
+    pub async fn history(&self) -> HashMap<GossipKey, VecDeque<GossipEntry>> {
+        self.entries.lock().await.clone()
+    }
+
+    /// Drops entries older than `cutoff` from every key's history, mirroring
+    /// the one-week trim the probe loop applies to its own local results.
+    pub async fn prune_older_than(&self, cutoff: DateTime<Local>) {
+        let mut entries = self.entries.lock().await;
+        for history in entries.values_mut() {
+            while history.front().is_some_and(|entry| entry.result.0 < cutoff) {
+                history.pop_front();
+            }
+        }
+    }
+}
+
+/// Encodes entries as one `agent_id,ip,rfc3339,mtu,latency_micros,sample_count`
+/// line each. The latency histogram itself isn't transmitted, only its
+/// total sample count: gossip carries the latest point-in-time reading
+/// from each vantage point (plus enough to reconcile it against another
+/// report for the same minute), not the full distribution.
+pub fn encode_entries(entries: &[(GossipKey, GossipEntry)]) -> String {
+    entries
+        .iter()
+        .map(|((agent_id, ip_address), entry)| {
+            format!(
+                "{agent_id},{ip_address},{},{},{},{}",
+                entry.result.0.to_rfc3339(),
+                entry.result.1.mtu,
+                entry.result.1.latency_micros,
+                entry.sample_count
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn decode_entries(body: &str) -> Vec<(GossipKey, GossipEntry)> {
+    body.lines().filter_map(decode_entry).collect()
+}
+
+fn decode_entry(line: &str) -> Option<(GossipKey, GossipEntry)> {
+    let mut fields = line.splitn(6, ',');
+    let agent_id = fields.next()?.to_string();
+    let ip_address: IpAddr = fields.next()?.parse().ok()?;
+    let timestamp: DateTime<Local> = DateTime::parse_from_rfc3339(fields.next()?)
+        .ok()?
+        .with_timezone(&Local);
+    let mtu = fields.next()?.parse().ok()?;
+    let latency_micros = fields.next()?.parse().ok()?;
+    let sample_count = fields.next()?.parse().ok()?;
+    Some((
+        (agent_id, ip_address),
+        GossipEntry {
+            result: (timestamp, PingResult::from_sample(mtu, latency_micros)),
+            sample_count,
+        },
+    ))
+}
+
+struct Peer {
+    address: String,
+    last_exchanged: Instant,
+}
+
+/// Tracks configured gossip peers and picks who to exchange state with each
+/// round, biasing toward peers not contacted recently so coverage spreads
+/// across the mesh instead of always hitting the same handful.
+pub struct PeerBook {
+    peers: Mutex<Vec<Peer>>,
+}
+
+impl PeerBook {
+    pub fn new(addresses: Vec<String>) -> Self {
+        let now = Instant::now();
+        Self {
+            peers: Mutex::new(
+                addresses
+                    .into_iter()
+                    .map(|address| Peer {
+                        address,
+                        last_exchanged: now,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Picks up to `count` peers via weighted random selection without
+    /// replacement, where each peer's weight is how long it's been since we
+    /// last gossiped with it.
+    pub async fn select_peers(&self, count: usize) -> Vec<String> {
+        let mut peers = self.peers.lock().await;
+        let now = Instant::now();
+        let mut remaining: Vec<usize> = (0..peers.len()).collect();
+        let mut selected = vec![];
+
+        while selected.len() < count && !remaining.is_empty() {
+            let weights: Vec<f64> = remaining
+                .iter()
+                .map(|&index| {
+                    now.duration_since(peers[index].last_exchanged)
+                        .as_secs_f64()
+                        .max(0.001)
+                })
+                .collect();
+            let total: f64 = weights.iter().sum();
+            let mut pick = rand::thread_rng().gen_range(0.0..total);
+            let mut chosen = 0;
+            for (position, &weight) in weights.iter().enumerate() {
+                if pick < weight {
+                    chosen = position;
+                    break;
+                }
+                pick -= weight;
+            }
+            let peer_index = remaining.remove(chosen);
+            peers[peer_index].last_exchanged = now;
+            selected.push(peers[peer_index].address.clone());
+        }
+
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry(latency_micros: u128, sample_count: u64) -> GossipEntry {
+        let timestamp = Local.with_ymd_and_hms(2026, 1, 2, 3, 4, 0).unwrap();
+        GossipEntry {
+            result: (timestamp, PingResult::from_sample(1400, latency_micros)),
+            sample_count,
+        }
+    }
+
+    #[test]
+    fn encode_then_decode_entries_round_trips_mtu_latency_and_sample_count() {
+        let key: GossipKey = ("agent-a".to_string(), "10.0.0.1".parse().unwrap());
+        let entries = vec![(key.clone(), entry(9_000, 3))];
+
+        let encoded = encode_entries(&entries);
+        let decoded = decode_entries(&encoded);
+
+        assert_eq!(decoded.len(), 1);
+        let (decoded_key, decoded_entry) = &decoded[0];
+        assert_eq!(*decoded_key, key);
+        assert_eq!(decoded_entry.result.1.mtu, 1400);
+        assert_eq!(decoded_entry.result.1.latency_micros, 9_000);
+        assert_eq!(decoded_entry.sample_count, 3);
+    }
+
+    #[test]
+    fn decode_entries_skips_malformed_lines_without_dropping_valid_ones() {
+        let key: GossipKey = ("agent-a".to_string(), "10.0.0.1".parse().unwrap());
+        let good_line = encode_entries(&[(key, entry(9_000, 1))]);
+        let body = format!("not,a,valid,line\n{good_line}");
+
+        assert_eq!(decode_entries(&body).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn merge_appends_a_new_minute_and_keeps_the_higher_sample_count_for_a_repeat() {
+        let store = GossipStore::default();
+        let key: GossipKey = ("agent-a".to_string(), "10.0.0.1".parse().unwrap());
+        let first_minute = entry(1_000, 1);
+        let first_minute_more_samples = entry(4_000, 5);
+        let second_minute = GossipEntry {
+            result: (
+                Local.with_ymd_and_hms(2026, 1, 2, 3, 5, 0).unwrap(),
+                PingResult::from_sample(1400, 3_000),
+            ),
+            sample_count: 1,
+        };
+
+        store.merge(key.clone(), first_minute).await;
+        store.merge(key.clone(), first_minute_more_samples.clone()).await;
+        store.merge(key.clone(), second_minute).await;
+
+        let history = store.history().await;
+        let series = history.get(&key).expect("key should have a history");
+        assert_eq!(series.len(), 2);
+        assert_eq!(series[0].sample_count, first_minute_more_samples.sample_count);
+        assert_eq!(series[0].result.1.latency_micros, 4_000);
+    }
+
+    #[tokio::test]
+    async fn merge_converges_to_the_same_state_regardless_of_arrival_order() {
+        let lower = entry(1_000, 1);
+        let higher = entry(4_000, 5);
+
+        let in_order = GossipStore::default();
+        let key: GossipKey = ("agent-a".to_string(), "10.0.0.1".parse().unwrap());
+        in_order.merge(key.clone(), lower.clone()).await;
+        in_order.merge(key.clone(), higher.clone()).await;
+
+        let out_of_order = GossipStore::default();
+        out_of_order.merge(key.clone(), higher).await;
+        out_of_order.merge(key.clone(), lower).await;
+
+        let in_order_latency = in_order.history().await[&key][0].result.1.latency_micros;
+        let out_of_order_latency = out_of_order.history().await[&key][0].result.1.latency_micros;
+        assert_eq!(in_order_latency, out_of_order_latency);
+        assert_eq!(in_order_latency, 4_000);
+    }
+}
@@ -0,0 +1,230 @@
+//! Log-linear histogram for tracking latency distributions in bounded memory.
+//!
+//! Values below `2^GROUPING_POWER` microseconds fall into linear, single-unit
+//! buckets. Above that threshold each power-of-two band (`[2^e, 2^(e+1))`) is
+//! split into `2^GROUPING_POWER` equal-width sub-buckets, so relative error
+//! stays within roughly `1 / 2^GROUPING_POWER` no matter how high latency
+//! climbs, while the bucket count stays fixed at a few thousand.
+//!
+//! Buckets are stored sparsely (only ones with a nonzero count take up
+//! space) rather than as a dense `TOTAL_BUCKETS`-length array. A `PingResult`
+//! carries one of these per retained minute, and `IpResults`/`GossipStore`
+//! between them can retain a week of per-minute history for several targets,
+//! so a dense histogram (tens of KB apiece) would multiply out to hundreds
+//! of MB; a real minute's worth of samples only ever touches a handful of
+//! buckets.
+
+use std::collections::HashMap;
+
+/// Values below `2^GROUPING_POWER` get one bucket per microsecond.
+const GROUPING_POWER: u32 = 7;
+/// Values are clamped to `2^MAX_VALUE_POWER - 1` microseconds (~12 days).
+const MAX_VALUE_POWER: u32 = 40;
+
+const LINEAR_BUCKETS: u32 = 1 << GROUPING_POWER;
+const TOTAL_BUCKETS: u32 = LINEAR_BUCKETS + (MAX_VALUE_POWER - GROUPING_POWER) * LINEAR_BUCKETS;
+const MAX_VALUE: u128 = (1u128 << MAX_VALUE_POWER) - 1;
+
+#[derive(Clone, Default)]
+pub struct LatencyHistogram {
+    buckets: HashMap<u32, u64>,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a single latency sample, in microseconds.
+    pub fn record(&mut self, micros: u128) {
+        let index = Self::bucket_index(micros.min(MAX_VALUE));
+        *self.buckets.entry(index).or_insert(0) += 1;
+    }
+
+    /// Merges `other`'s counts into `self`, used when several samples land in
+    /// the same minute bucket.
+    pub fn merge(&mut self, other: &Self) {
+        for (&index, &count) in &other.buckets {
+            *self.buckets.entry(index).or_insert(0) += count;
+        }
+    }
+
+    /// Total number of samples recorded.
+    pub fn total(&self) -> u64 {
+        self.buckets.values().sum()
+    }
+
+    /// Number of recorded samples in buckets up to and including the one
+    /// holding `threshold` microseconds, used to render cumulative
+    /// Prometheus histogram buckets. Samples that share `threshold`'s bucket
+    /// are counted even when slightly above it.
+    pub fn count_le(&self, threshold: u128) -> u64 {
+        let index = Self::bucket_index(threshold.min(MAX_VALUE));
+        self.buckets
+            .iter()
+            .filter(|&(&bucket_index, _)| bucket_index <= index)
+            .map(|(_, &count)| count)
+            .sum()
+    }
+
+    /// Returns an estimate of the `p`th percentile latency in microseconds,
+    /// where `p` is in `[0.0, 1.0]`. Returns `0` if no samples were recorded.
+    pub fn percentile(&self, p: f64) -> u128 {
+        let total = self.total();
+        if total == 0 {
+            return 0;
+        }
+
+        #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let target = ((p.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+
+        let mut occupied: Vec<(u32, u64)> = self.buckets.iter().map(|(&index, &count)| (index, count)).collect();
+        occupied.sort_unstable_by_key(|&(index, _)| index);
+
+        let mut cumulative = 0u64;
+        for (index, count) in occupied {
+            cumulative += count;
+            if cumulative >= target {
+                return Self::bucket_lower_bound(index);
+            }
+        }
+        Self::bucket_lower_bound(TOTAL_BUCKETS - 1)
+    }
+
+    /// Iterates over `(bucket_index, count)` for every bucket with at least
+    /// one sample, for compact serialization.
+    pub fn nonzero_buckets(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.buckets.iter().map(|(&index, &count)| (index as usize, count))
+    }
+
+    /// Sets a bucket's count directly, used when reloading from a persisted
+    /// sparse encoding.
+    pub fn set_bucket(&mut self, index: usize, count: u64) {
+        if let Ok(index) = u32::try_from(index) {
+            if index < TOTAL_BUCKETS {
+                self.buckets.insert(index, count);
+            }
+        }
+    }
+
+    fn bucket_index(value: u128) -> u32 {
+        if value < u128::from(LINEAR_BUCKETS) {
+            return value as u32;
+        }
+
+        let exponent = 127 - value.leading_zeros();
+        let band_width = 1u128 << (exponent - GROUPING_POWER);
+        let sub_index = ((value - (1u128 << exponent)) / band_width) as u32;
+        LINEAR_BUCKETS + (exponent - GROUPING_POWER) * LINEAR_BUCKETS + sub_index
+    }
+
+    fn bucket_lower_bound(index: u32) -> u128 {
+        if index < LINEAR_BUCKETS {
+            return u128::from(index);
+        }
+
+        let band = (index - LINEAR_BUCKETS) / LINEAR_BUCKETS;
+        let sub_index = (index - LINEAR_BUCKETS) % LINEAR_BUCKETS;
+        let exponent = GROUPING_POWER + band;
+        let band_width = 1u128 << (exponent - GROUPING_POWER);
+        (1u128 << exponent) + u128::from(sub_index) * band_width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_values_get_one_bucket_per_microsecond() {
+        assert_eq!(LatencyHistogram::bucket_index(0), 0);
+        assert_eq!(LatencyHistogram::bucket_index(1), 1);
+        assert_eq!(
+            LatencyHistogram::bucket_index(u128::from(LINEAR_BUCKETS) - 1),
+            LINEAR_BUCKETS - 1
+        );
+    }
+
+    #[test]
+    fn first_log_band_starts_right_after_the_linear_range() {
+        assert_eq!(
+            LatencyHistogram::bucket_index(u128::from(LINEAR_BUCKETS)),
+            LINEAR_BUCKETS
+        );
+    }
+
+    #[test]
+    fn bucket_lower_bound_is_the_inverse_of_bucket_index() {
+        for value in [0u128, 1, 63, 127, 128, 129, 1_000, 1_000_000, 999_999_999] {
+            let index = LatencyHistogram::bucket_index(value);
+            let lower_bound = LatencyHistogram::bucket_lower_bound(index);
+            assert!(
+                lower_bound <= value,
+                "bucket {index}'s lower bound {lower_bound} is above the value {value} that landed in it"
+            );
+            assert!(
+                LatencyHistogram::bucket_index(lower_bound) == index,
+                "bucket {index}'s own lower bound {lower_bound} maps back to a different bucket"
+            );
+        }
+    }
+
+    #[test]
+    fn values_above_max_are_clamped_into_the_last_bucket() {
+        let over_max = MAX_VALUE + 1_000_000;
+        assert_eq!(
+            LatencyHistogram::bucket_index(over_max.min(MAX_VALUE)),
+            TOTAL_BUCKETS - 1
+        );
+    }
+
+    #[test]
+    fn percentile_of_an_empty_histogram_is_zero() {
+        let histogram = LatencyHistogram::new();
+        assert_eq!(histogram.percentile(0.5), 0);
+    }
+
+    #[test]
+    fn percentile_picks_the_bucket_covering_the_requested_fraction() {
+        let mut histogram = LatencyHistogram::new();
+        for _ in 0..9 {
+            histogram.record(10);
+        }
+        histogram.record(1_000_000);
+
+        assert_eq!(histogram.percentile(0.5), 10);
+        assert!(histogram.percentile(0.99) > 10);
+    }
+
+    #[test]
+    fn merge_adds_bucket_counts_from_both_histograms() {
+        let mut a = LatencyHistogram::new();
+        a.record(5);
+        let mut b = LatencyHistogram::new();
+        b.record(5);
+        b.record(20);
+
+        a.merge(&b);
+
+        assert_eq!(a.total(), 3);
+        assert_eq!(a.count_le(10), 2);
+        assert_eq!(a.count_le(20), 3);
+    }
+
+    #[test]
+    fn nonzero_buckets_round_trips_through_set_bucket() {
+        let mut original = LatencyHistogram::new();
+        original.record(3);
+        original.record(3);
+        original.record(500_000);
+
+        let mut restored = LatencyHistogram::new();
+        for (index, count) in original.nonzero_buckets() {
+            restored.set_bucket(index, count);
+        }
+
+        assert_eq!(restored.total(), original.total());
+        assert_eq!(restored.percentile(0.5), original.percentile(0.5));
+        assert_eq!(restored.percentile(0.99), original.percentile(0.99));
+    }
+}
@@ -1,26 +1,40 @@
+mod error;
+mod gossip;
+mod histogram;
+mod metrics;
 mod model;
+mod persistence;
+mod rate_limiter;
 
+use crate::error::Error;
+use crate::gossip::{decode_entries, encode_entries, GossipEntry, GossipKey, GossipStore, PeerBook};
+use crate::metrics::{render_prometheus_metrics, FailedProbeCounters, HistogramCache, ThrottledCounters};
 use crate::model::{IpResults, MetricType, PingResult};
+use crate::persistence::PersistenceLog;
+use crate::rate_limiter::RateLimiter;
 use chrono::{Datelike, Duration, Local, Timelike};
 use dotenvy::dotenv;
 use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::env;
 use std::net::IpAddr;
 use std::sync::Arc;
-use warp::Filter;
 use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use warp::Filter;
 
 const NEW_CLEAR_LINE: &str = "\n\x1b[K";
 const MOVE_CURSOR_UP: &str = "\r\x1b[";
-const MTU_STEP: usize = 4;
+const LATENCY_PERCENTILES: [f64; 3] = [0.5, 0.9, 0.99];
 
 const PING_OPTIONS: ping_rs::PingOptions = ping_rs::PingOptions {
     ttl: 128,
     dont_fragment: true,
 };
 
-fn check_connectivity(ip_address: &IpAddr, mtu_size: usize) -> Option<u128> {
+fn check_connectivity(ip_address: &IpAddr, mtu_size: usize) -> Result<u128, Error> {
     let timeout = std::time::Duration::from_secs(1);
     let data: Vec<u8> = vec![0; mtu_size];
 
@@ -28,27 +42,66 @@ fn check_connectivity(ip_address: &IpAddr, mtu_size: usize) -> Option<u128> {
     let result = ping_rs::send_ping(ip_address, timeout, &data, Some(&PING_OPTIONS));
     let latency_micros = start_time.elapsed().as_micros();
 
-    if result.is_ok() {
-        Some(latency_micros)
-    } else {
-        None
-    }
+    result.map(|_| latency_micros).map_err(|error| Error::Ping {
+        ip_address: *ip_address,
+        detail: format!("{error:?}"),
+    })
 }
 
+/// Binary searches `[min_mtu_size, max_mtu_size]` for the largest MTU size
+/// that gets a reply, assuming connectivity is monotonic in packet size
+/// (smaller packets are at least as likely to get through as larger ones).
+/// Returns `Ok(None)` if every size failed to reach the host, but returns
+/// early with `Err` as soon as a failure looks like a raw-socket permission
+/// problem rather than an unreachable host, since no amount of retrying will
+/// fix that.
+///
+/// The search itself is factored out into [`binary_search_mtu`], which takes
+/// the probe as an injectable closure so the algorithm can be unit tested
+/// without sending real packets.
 fn check_connectivity_with_mtu(
     ip_address: &IpAddr,
     min_mtu_size: usize,
     max_mtu_size: usize,
-) -> Option<PingResult> {
-    for mtu_size in (min_mtu_size..=max_mtu_size).rev().step_by(MTU_STEP) {
-        if let Some(latency_micros) = check_connectivity(ip_address, mtu_size) {
-            return Some(PingResult {
-                mtu: mtu_size,
-                latency_micros,
-            }); // Return the successful MTU size
+) -> Result<Option<PingResult>, Error> {
+    binary_search_mtu(min_mtu_size, max_mtu_size, |mtu_size| {
+        check_connectivity(ip_address, mtu_size)
+    })
+}
+
+/// The pure binary-search algorithm behind [`check_connectivity_with_mtu`].
+/// `probe` plays the role of `check_connectivity`: `Ok(latency_micros)` for a
+/// reply, `Err` for a failure, with a permission-denied error aborting the
+/// search immediately rather than being treated as "this size is too big".
+fn binary_search_mtu<F>(min_mtu_size: usize, max_mtu_size: usize, mut probe: F) -> Result<Option<PingResult>, Error>
+where
+    F: FnMut(usize) -> Result<u128, Error>,
+{
+    let mut low = min_mtu_size;
+    let mut high = max_mtu_size;
+    let mut best = None;
+
+    while low <= high {
+        let mid = low + (high - low) / 2;
+        match probe(mid) {
+            Ok(latency_micros) => {
+                best = Some(PingResult::from_sample(mid, latency_micros));
+                if mid == max_mtu_size {
+                    break;
+                }
+                low = mid + 1;
+            }
+            Err(error) if error.is_permission_denied() => return Err(error),
+            Err(_) => {
+                if mid == min_mtu_size {
+                    break;
+                }
+                high = mid - 1;
+            }
         }
     }
-    None // Return None if all MTU sizes fail
+
+    Ok(best)
 }
 
 #[allow(clippy::cast_precision_loss)]
@@ -90,6 +143,9 @@ async fn get_rows_for_html_graph(
                 .map_or(0.0, |(_, ping_result)| match metric_type {
                     MetricType::Mtu => ping_result.mtu as f64,
                     MetricType::Latency => ping_result.latency_micros as f64,
+                    MetricType::LatencyPercentile(p) => {
+                        ping_result.latency_histogram.percentile(*p) as f64
+                    }
                 }); // If no data for that timestamp, use 0
             row.push_str(&format!("{mtu_size}"));
             if i < ip_addresses.len() - 1 {
@@ -102,45 +158,213 @@ async fn get_rows_for_html_graph(
     rows.join(",\n")
 }
 
-#[tokio::main]
-async fn main() {
-    dotenv().ok();
+/// Like [`get_rows_for_html_graph`], but emits one column per
+/// `(metric_type, ip_address)` pair within a single row per timestamp, so
+/// several related series (e.g. latency percentiles) can share one chart.
+#[allow(clippy::cast_precision_loss)]
+async fn get_rows_for_html_graph_multi(
+    results: &IpResults,
+    ip_addresses: &[IpAddr],
+    metric_types: &[MetricType],
+) -> String {
+    let mut rows = vec![];
 
-    let ip_addresses: Vec<IpAddr> = env::var("IP_ADDRESSES")
-        .expect("IP_ADDRESSES must be set in .env")
-        .split(',')
-        .map(|ip| ip.trim().parse().expect("Invalid IP address format"))
-        .collect();
+    let mut timestamps_set = BTreeSet::new();
+    for ip in ip_addresses {
+        let timestamps = results.get(ip).unwrap().lock().await;
+        for &(timestamp, _) in timestamps.iter() {
+            timestamps_set.insert(timestamp);
+        }
+    }
 
-    let min_mtu_size: usize = env::var("MIN_MTU_SIZE")
-        .expect("MIN_MTU_SIZE must be set in .env")
-        .parse()
-        .expect("Invalid MIN_MTU_SIZE");
-    let max_mtu_size: usize = env::var("MAX_MTU_SIZE")
-        .expect("MAX_MTU_SIZE must be set in .env")
-        .parse()
-        .expect("Invalid MAX_MTU_SIZE");
+    for timestamp in timestamps_set {
+        let mut row = format!(
+            "[new Date({}, {}, {}, {}, {}), ",
+            timestamp.year(),
+            timestamp.month() - 1,
+            timestamp.day(),
+            timestamp.hour(),
+            timestamp.minute()
+        );
 
-    let interval_millis: u64 = env::var("INTERVAL_MILLIS")
-        .expect("INTERVAL_MILLIS must be set in .env")
-        .parse()
-        .expect("Invalid INTERVAL_MILLIS");
+        let mut values = vec![];
+        for metric_type in metric_types {
+            for ip in ip_addresses {
+                let value = results
+                    .get(ip)
+                    .unwrap()
+                    .lock()
+                    .await
+                    .iter()
+                    .find(|&&(ts, _)| ts == timestamp)
+                    .map_or(0.0, |(_, ping_result)| match metric_type {
+                        MetricType::Mtu => ping_result.mtu as f64,
+                        MetricType::Latency => ping_result.latency_micros as f64,
+                        MetricType::LatencyPercentile(p) => {
+                            ping_result.latency_histogram.percentile(*p) as f64
+                        }
+                    });
+                values.push(format!("{value}"));
+            }
+        }
+        row.push_str(&values.join(", "));
+        row.push(']');
+        rows.push(row);
+    }
+    rows.join(",\n")
+}
+
+/// Escapes characters that would end a single-quoted JavaScript string
+/// literal, so an untrusted string embedded inside the report's inline
+/// `<script>` source (a gossiped agent id, in particular) can't break out
+/// into arbitrary script.
+fn escape_js_string(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\'', "\\'")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Renders one row per timestamp with one latency column per `(agent, ip)`
+/// key in `keys`, so reachability as seen from every vantage point in the
+/// mesh shows up on a single chart rather than just this agent's own.
+/// `history` is an already-unlocked snapshot, so unlike its `IpResults`
+/// counterparts this doesn't need to be `async`.
+#[allow(clippy::cast_precision_loss)]
+fn get_rows_for_gossip_graph(
+    history: &HashMap<GossipKey, VecDeque<GossipEntry>>,
+    keys: &[GossipKey],
+) -> String {
+    let mut timestamps_set = BTreeSet::new();
+    for key in keys {
+        if let Some(series) = history.get(key) {
+            timestamps_set.extend(series.iter().map(|entry| entry.result.0));
+        }
+    }
+
+    let mut rows = vec![];
+    for timestamp in timestamps_set {
+        let mut row = format!(
+            "[new Date({}, {}, {}, {}, {}), ",
+            timestamp.year(),
+            timestamp.month() - 1,
+            timestamp.day(),
+            timestamp.hour(),
+            timestamp.minute()
+        );
+
+        let values: Vec<String> = keys
+            .iter()
+            .map(|key| {
+                let latency_micros = history
+                    .get(key)
+                    .and_then(|series| series.iter().find(|entry| entry.result.0 == timestamp))
+                    .map_or(0.0, |entry| entry.result.1.latency_micros as f64);
+                format!("{latency_micros}")
+            })
+            .collect();
+        row.push_str(&values.join(", "));
+        row.push(']');
+        rows.push(row);
+    }
+    rows.join(",\n")
+}
+
+/// Reads a required `.env`/environment variable, turning an absent value
+/// into an actionable [`Error`] instead of a panic.
+fn env_var(name: &'static str) -> Result<String, Error> {
+    env::var(name).map_err(|_| Error::MissingEnvVar(name))
+}
 
-    let port: u16 = env::var("PORT")
-        .expect("PORT must be set in .env")
+/// Reads and parses a required numeric environment variable.
+fn parse_env_var<T>(name: &'static str) -> Result<T, Error>
+where
+    T: std::str::FromStr<Err = std::num::ParseIntError>,
+{
+    env_var(name)?
         .parse()
-        .expect("Invalid PORT");
+        .map_err(|source| Error::InvalidConfigValue { name, source })
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    dotenv().ok();
+
+    let mut ip_addresses: Vec<IpAddr> = env_var("IP_ADDRESSES")?
+        .split(',')
+        .map(|ip| {
+            let ip = ip.trim();
+            ip.parse().map_err(|source| Error::InvalidIpAddress {
+                value: ip.to_string(),
+                source,
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    // A repeated address in IP_ADDRESSES is a config typo, not a parse
+    // error; dedupe up front so the per-tick `HashMap` keyed by address
+    // below can't be asked to remove the same entry twice.
+    let mut seen_ip_addresses = HashSet::new();
+    ip_addresses.retain(|ip_address| seen_ip_addresses.insert(*ip_address));
+
+    let min_mtu_size: usize = parse_env_var("MIN_MTU_SIZE")?;
+    let max_mtu_size: usize = parse_env_var("MAX_MTU_SIZE")?;
+    let interval_millis: u64 = parse_env_var("INTERVAL_MILLIS")?;
+    let packets_per_second: u64 = parse_env_var("PACKETS_PER_SECOND")?;
+    let burst_size: u64 = parse_env_var("BURST_SIZE")?;
+    let port: u16 = parse_env_var("PORT")?;
+    let persistence_dir = env_var("PERSISTENCE_DIR")?;
+    let agent_id = env_var("AGENT_ID")?;
+    let gossip_fanout: usize = parse_env_var("GOSSIP_FANOUT")?;
+    let peers: Vec<String> = env_var("PEERS")?
+        .split(',')
+        .map(str::trim)
+        .filter(|peer| !peer.is_empty())
+        .map(str::to_string)
+        .collect();
 
     // Clone ip_addresses before moving it into the async closure
     let ip_addresses_clone = ip_addresses.clone();
 
-    let results: IpResults = ip_addresses
-        .iter()
-        .map(|ip| (*ip, Arc::new(Mutex::new(VecDeque::new()))))
-        .collect();
+    let persistence = Arc::new(PersistenceLog::new(persistence_dir));
+    let results: IpResults = persistence.load(&ip_addresses);
 
     let results_clone = results.clone();
 
+    let failed_probes = Arc::new(FailedProbeCounters::default());
+    let failed_probes_clone = failed_probes.clone();
+
+    let throttled_probes = Arc::new(ThrottledCounters::default());
+    let throttled_probes_clone = throttled_probes.clone();
+
+    let histogram_cache = Arc::new(HistogramCache::default());
+    let histogram_cache_clone = histogram_cache.clone();
+
+    let rate_limiter = Arc::new(RateLimiter::new(packets_per_second, burst_size));
+
+    let gossip_store = Arc::new(GossipStore::default());
+    let peer_book = Arc::new(PeerBook::new(peers));
+    let gossip_store_probe = gossip_store.clone();
+    let peer_book_probe = peer_book.clone();
+
+    let persistence_flush = persistence.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            if let Err(error) = persistence_flush.flush().await {
+                eprintln!("failed to flush persistence log: {error}");
+            }
+        }
+    });
+
+    // A raw-socket permission problem affects every target equally and won't
+    // clear up by retrying, so the probe loop reports it here instead of
+    // aborting the process directly: that lets `main` flush the persistence
+    // log and return the error through its normal `Result` contract rather
+    // than skipping both on the way out.
+    let persistence_for_fatal = persistence.clone();
+    let (fatal_tx, mut fatal_rx) = tokio::sync::oneshot::channel::<Error>();
+
     tokio::spawn(async move {
         println!("start time {}", Local::now());
 
@@ -151,46 +375,145 @@ async fn main() {
                 .with_nanosecond(0)
                 .unwrap();
 
+            // Probe every target concurrently so one slow host can't stall
+            // the others and drift the sampling interval. The blocking
+            // `ping_rs` calls (and the binary search's several round trips)
+            // each run on their own blocking thread. Targets that are over
+            // their token-bucket budget are skipped for this tick entirely.
+            let mut probe_tasks = JoinSet::new();
+            let mut throttled = HashSet::new();
+            for ip_address in ip_addresses_clone.iter().copied() {
+                if rate_limiter.try_acquire(ip_address).await {
+                    probe_tasks.spawn_blocking(move || {
+                        (
+                            ip_address,
+                            check_connectivity_with_mtu(&ip_address, min_mtu_size, max_mtu_size),
+                        )
+                    });
+                } else {
+                    throttled.insert(ip_address);
+                }
+            }
+
+            let mut probe_results = HashMap::new();
+            while let Some(task_result) = probe_tasks.join_next().await {
+                let (ip_address, probe_result) = task_result.expect("probe task panicked");
+                probe_results.insert(ip_address, probe_result);
+            }
+
             print!("{MOVE_CURSOR_UP}{}A", &ip_addresses_clone.len() + 1);
             for ip_address in &ip_addresses_clone {
-                // Check for successful MTU size
-                let ping_result =
-                    check_connectivity_with_mtu(ip_address, min_mtu_size, max_mtu_size).unwrap_or(
-                        PingResult {
-                            mtu: 0,
-                            latency_micros: 1_000_000,
-                        },
-                    );
+                if throttled.contains(ip_address) {
+                    print!("{NEW_CLEAR_LINE}{ip_address}: throttled (rate limit)");
+                    throttled_probes_clone.increment(*ip_address).await;
+                    continue;
+                }
+                let probe_result = match probe_results.remove(ip_address).unwrap() {
+                    Ok(result) => result,
+                    // A raw-socket permission problem affects every target
+                    // equally and won't clear up by retrying, so rather than
+                    // fold it into the same "unreachable" sentinel as every
+                    // other failure, say so plainly and stop the whole agent.
+                    Err(error) if error.is_permission_denied() => {
+                        eprintln!(
+                            "{NEW_CLEAR_LINE}{ip_address}: {error} (raw-socket permission denied; exiting)"
+                        );
+                        let _ = fatal_tx.send(error);
+                        return;
+                    }
+                    Err(error) => {
+                        eprintln!("{NEW_CLEAR_LINE}{ip_address}: {error}");
+                        None
+                    }
+                };
+                let succeeded = probe_result.is_some();
+                if !succeeded {
+                    failed_probes_clone.increment(*ip_address).await;
+                }
+                // A failed probe gets a sentinel latency so it still shows up
+                // as a spike on the raw MTU/latency chart, but an empty
+                // histogram so it doesn't distort the percentile charts or
+                // the Prometheus histogram: the failure signal itself is
+                // `failed_probes`/`uptime_failed_probes_total`, not a
+                // fabricated latency sample.
+                let ping_result = probe_result.unwrap_or(PingResult::failed(1_000_000));
+                if succeeded {
+                    histogram_cache_clone
+                        .record(*ip_address, ping_result.latency_micros)
+                        .await;
+                }
                 let mut results_lock = results_clone.get(ip_address).unwrap().lock().await;
                 // Check if we already have an entry for the current minute
-                if let Some((last_time, last_result)) = results_lock.pop_back() {
-                    // If it's the same minute and latency is higher, update it
+                let current_minute_result = if let Some((last_time, mut last_result)) = results_lock.pop_back() {
+                    // If it's the same minute, fold this sample into the running histogram
                     if last_time == current_minute {
-                        results_lock.push_back((
-                            current_minute,
-                            PingResult {
-                                mtu: last_result.mtu.min(ping_result.mtu),
-                                latency_micros: last_result
-                                    .latency_micros
-                                    .max(ping_result.latency_micros),
-                            },
-                        ));
+                        last_result.merge_sample(&ping_result);
+                        results_lock.push_back((current_minute, last_result.clone()));
+                        last_result
                     } else {
+                        // last_time's minute is now finalized; persist it
+                        persistence.enqueue(*ip_address, (last_time, last_result.clone())).await;
                         results_lock.push_back((last_time, last_result));
                         results_lock.push_back((current_minute, ping_result.clone()));
+                        ping_result.clone()
                     }
                 } else {
                     // Add to results if no entry exists
                     results_lock.push_back((current_minute, ping_result.clone()));
-                }
+                    ping_result.clone()
+                };
+                drop(results_lock);
                 print!(
                     "{NEW_CLEAR_LINE}{ip_address}: MTU {} latency {} micros",
                     ping_result.mtu, ping_result.latency_micros
                 );
+
+                // Gossip the running cumulative value for this minute, not
+                // just this tick's sample, along with how many real samples
+                // it reflects: that count is what lets a peer reconcile two
+                // reports for the same still-in-progress minute consistently
+                // regardless of which one it receives first.
+                gossip_store_probe
+                    .merge(
+                        (agent_id.clone(), *ip_address),
+                        GossipEntry {
+                            sample_count: current_minute_result.latency_histogram.total(),
+                            result: (current_minute, current_minute_result),
+                        },
+                    )
+                    .await;
             }
 
             println!();
 
+            // Exchange mesh state with a weighted-random subset of peers in
+            // the background so a slow/unreachable peer can't stall probing.
+            let gossip_store_round = gossip_store_probe.clone();
+            let peer_book_round = peer_book_probe.clone();
+            tokio::spawn(async move {
+                let peers = peer_book_round.select_peers(gossip_fanout).await;
+                if peers.is_empty() {
+                    return;
+                }
+                let client = reqwest::Client::new();
+                for peer in peers {
+                    let push_body = encode_entries(&gossip_store_round.snapshot().await);
+                    let _ = client
+                        .post(format!("http://{peer}/gossip"))
+                        .body(push_body)
+                        .send()
+                        .await;
+
+                    if let Ok(response) = client.get(format!("http://{peer}/gossip")).send().await {
+                        if let Ok(text) = response.text().await {
+                            for (key, entry) in decode_entries(&text) {
+                                gossip_store_round.merge(key, entry).await;
+                            }
+                        }
+                    }
+                }
+            });
+
             // Remove old results older than one week
             let one_week_ago = current_minute - Duration::days(7);
             for ip_address in &ip_addresses_clone {
@@ -203,16 +526,70 @@ async fn main() {
                     results_lock.pop_front();
                 }
             }
+            if let Err(error) = persistence.compact(&ip_addresses_clone).await {
+                eprintln!("failed to compact persistence log: {error}");
+            }
+            gossip_store_probe.prune_older_than(one_week_ago).await;
 
             tokio::time::sleep(std::time::Duration::from_millis(interval_millis)).await;
         }
     });
 
+    // Gossip endpoints: GET returns this agent's full mesh snapshot, POST
+    // merges a peer's pushed entries into it.
+    let gossip_store_for_pull = gossip_store.clone();
+    let gossip_pull_route = warp::path("gossip").and(warp::get()).and_then(move || {
+        let gossip_store = gossip_store_for_pull.clone();
+        async move { Ok::<_, warp::Rejection>(encode_entries(&gossip_store.snapshot().await)) }
+    });
+
+    let gossip_store_for_push = gossip_store.clone();
+    let gossip_push_route = warp::path("gossip")
+        .and(warp::post())
+        .and(warp::body::bytes())
+        .and_then(move |body: bytes::Bytes| {
+            let gossip_store = gossip_store_for_push.clone();
+            async move {
+                for (key, entry) in decode_entries(&String::from_utf8_lossy(&body)) {
+                    gossip_store.merge(key, entry).await;
+                }
+                Ok::<_, warp::Rejection>(warp::reply())
+            }
+        });
+
+    // Expose probe results in Prometheus text exposition format for scraping
+    let results_for_metrics = results.clone();
+    let ip_addresses_for_metrics = ip_addresses.clone();
+    let metrics_route = warp::path("metrics").and_then(move || {
+        let results_clone = results_for_metrics.clone();
+        let ip_addresses = ip_addresses_for_metrics.clone();
+        let failed_probes = failed_probes.clone();
+        let throttled_probes = throttled_probes.clone();
+        let histogram_cache = histogram_cache.clone();
+        async move {
+            let body = render_prometheus_metrics(
+                &results_clone,
+                &ip_addresses,
+                &failed_probes,
+                &throttled_probes,
+                &histogram_cache,
+            )
+            .await;
+            Ok::<_, warp::Rejection>(warp::reply::with_header(
+                body,
+                "Content-Type",
+                "text/plain; version=0.0.4",
+            ))
+        }
+    });
+
     // Serve the HTML version that graphs the MTU size of the most recent successful pings
+    let gossip_store_for_report = gossip_store.clone();
     let report_route = warp::path::end()
         .and_then(move || {
             let results_clone = results.clone();
             let ip_addresses = ip_addresses.clone(); // Re-use the original ip_addresses
+            let gossip_store = gossip_store_for_report.clone();
             async move {
                 let mut html = String::from("<html>
       <head>
@@ -222,14 +599,20 @@ async fn main() {
           google.charts.setOnLoadCallback(drawChart);
           function drawChart() {");
 
-                let rows1 = get_rows_for_html_graph(&results_clone, &ip_addresses, &MetricType::Latency).await;
+                // Plot latency as seen from every vantage point in the mesh
+                // (this agent's own probes are in here too: the probe loop
+                // feeds its own results into `gossip_store` the same way it
+                // does a peer's), one column per `(agent, ip)` key.
+                let mesh_history = gossip_store.history().await;
+                let mut mesh_keys: Vec<GossipKey> = mesh_history.keys().cloned().collect();
+                mesh_keys.sort();
+                let rows1 = get_rows_for_gossip_graph(&mesh_history, &mesh_keys);
 
-                // Prepare the column definitions
                 let mut columns1 = String::from("data1.addColumn('date', 'Date');\n");
-                for ip_address in &ip_addresses {
+                for (agent_id, ip_address) in &mesh_keys {
                     columns1.push_str(&format!(
-                        "data1.addColumn('number', '{ip_address}');\n",
-
+                        "data1.addColumn('number', '{}@{ip_address}');\n",
+                        escape_js_string(agent_id),
                     ));
                 }
                 html = format!(r#"{html}
@@ -276,6 +659,39 @@ async fn main() {
             "#);
                 }
 
+                let percentile_metrics: Vec<MetricType> = LATENCY_PERCENTILES
+                    .iter()
+                    .map(|&p| MetricType::LatencyPercentile(p))
+                    .collect();
+                let mut columns3 = String::from("data3.addColumn('date', 'Date');\n");
+                for &p in &LATENCY_PERCENTILES {
+                    for ip_address in &ip_addresses {
+                        columns3.push_str(&format!(
+                            "data3.addColumn('number', '{ip_address} p{}');\n",
+                            (p * 100.0) as u32
+                        ));
+                    }
+                }
+                let rows3 =
+                    get_rows_for_html_graph_multi(&results_clone, &ip_addresses, &percentile_metrics).await;
+
+                html = format!(r#"{html}
+                 var data3 = new google.visualization.DataTable();
+            {columns3}
+            data3.addRows([
+                {rows3}
+            ]);
+
+            var chart3 = new google.visualization.AnnotationChart(document.getElementById('chart_div3'));
+            chart3.draw(data3, {{
+              displayAnnotations: true,
+              scaleType: 'allfixed',
+              legendPosition: 'newRow',
+              thickness: 2,
+              zoomStartTime: new Date(new Date().getTime() - 24*60*60*1000)  // Start from 24 hours ago
+            }});
+            "#);
+
                 html = format!(
                     r#"{html}
           }}
@@ -285,6 +701,7 @@ async fn main() {
       <body>
         <div id='chart_div1' style='width: 900px; height: 500px;'></div>
         <div id='chart_div2' style='width: 900px; height: 500px;'></div>
+        <div id='chart_div3' style='width: 900px; height: 500px;'></div>
       </body>
     </html>
     "#
@@ -294,5 +711,84 @@ async fn main() {
         });
 
     println!("Report also available via HTTP port {port}");
-    warp::serve(report_route).run(([0, 0, 0, 0], port)).await;
+    let routes = report_route
+        .or(metrics_route)
+        .or(gossip_pull_route)
+        .or(gossip_push_route);
+    tokio::select! {
+        () = warp::serve(routes).run(([0, 0, 0, 0], port)) => Ok(()),
+        fatal = &mut fatal_rx => {
+            let Ok(error) = fatal else {
+                return Ok(());
+            };
+            if let Err(flush_error) = persistence_for_fatal.flush().await {
+                eprintln!("failed to flush persistence log during shutdown: {flush_error}");
+            }
+            Err(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permission_denied(ip_address: IpAddr) -> Error {
+        Error::Ping {
+            ip_address,
+            detail: "os error 13".to_string(),
+        }
+    }
+
+    fn unreachable(ip_address: IpAddr) -> Error {
+        Error::Ping {
+            ip_address,
+            detail: "timed out".to_string(),
+        }
+    }
+
+    #[test]
+    fn finds_the_largest_mtu_when_every_size_succeeds() {
+        let result = binary_search_mtu(1000, 1500, |mtu_size| Ok(u128::from(mtu_size as u64)))
+            .unwrap()
+            .expect("some size should have succeeded");
+        assert_eq!(result.mtu, 1500);
+    }
+
+    #[test]
+    fn returns_none_when_every_size_fails() {
+        let ip_address: IpAddr = "10.0.0.1".parse().unwrap();
+        let result = binary_search_mtu(1000, 1500, |_| Err(unreachable(ip_address))).unwrap();
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn finds_the_threshold_where_connectivity_stops() {
+        let ip_address: IpAddr = "10.0.0.1".parse().unwrap();
+        let threshold = 1200;
+        let result = binary_search_mtu(1000, 1500, |mtu_size| {
+            if mtu_size <= threshold {
+                Ok(42)
+            } else {
+                Err(unreachable(ip_address))
+            }
+        })
+        .unwrap()
+        .expect("sizes at or below the threshold should have succeeded");
+        assert_eq!(result.mtu, threshold);
+        assert_eq!(result.latency_micros, 42);
+    }
+
+    #[test]
+    fn bails_out_immediately_on_permission_denied() {
+        let ip_address: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut probe_calls = 0;
+        let result = binary_search_mtu(1000, 1500, |_| {
+            probe_calls += 1;
+            Err(permission_denied(ip_address))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(probe_calls, 1);
+    }
 }
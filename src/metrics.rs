@@ -0,0 +1,259 @@
+//! Prometheus text-exposition rendering for probe results. Scraped into
+//! Grafana/alerting pipelines alongside the built-in HTML report.
+
+use crate::histogram::LatencyHistogram;
+use crate::model::IpResults;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::Mutex;
+
+/// A per-IP latency histogram kept up to date one sample at a time, so a
+/// `/metrics` scrape never has to re-merge the full retained history (which
+/// can be thousands of per-minute histograms deep) under the same lock the
+/// probe loop needs.
+#[derive(Default)]
+struct HistogramEntry {
+    histogram: LatencyHistogram,
+    sum_micros: u64,
+}
+
+#[derive(Default)]
+pub struct HistogramCache {
+    entries: Mutex<HashMap<IpAddr, HistogramEntry>>,
+}
+
+impl HistogramCache {
+    /// Folds one more latency sample, in microseconds, into `ip_address`'s
+    /// running histogram. Call this once per successful probe; failed
+    /// probes are counted by `FailedProbeCounters` instead, and are left out
+    /// here just as they are left out of each minute's latency histogram.
+    pub async fn record(&self, ip_address: IpAddr, latency_micros: u128) {
+        let mut entries = self.entries.lock().await;
+        let entry = entries.entry(ip_address).or_default();
+        entry.histogram.record(latency_micros);
+        entry.sum_micros = entry
+            .sum_micros
+            .saturating_add(u64::try_from(latency_micros).unwrap_or(u64::MAX));
+    }
+
+    async fn snapshot(&self, ip_address: &IpAddr) -> (LatencyHistogram, u64) {
+        self.entries.lock().await.get(ip_address).map_or_else(
+            || (LatencyHistogram::new(), 0),
+            |entry| (entry.histogram.clone(), entry.sum_micros),
+        )
+    }
+}
+
+/// Upper bounds (microseconds) for the exported latency histogram buckets.
+const LATENCY_BUCKET_BOUNDS_MICROS: [u128; 7] =
+    [1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000];
+
+/// A simple monotonically increasing counter.
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Per-IP counters for probes where `check_connectivity_with_mtu` returned
+/// `Ok(None)` or a non-fatal `Err`, updated from inside the probe loop.
+#[derive(Default)]
+pub struct FailedProbeCounters {
+    counters: Mutex<HashMap<IpAddr, Counter>>,
+}
+
+impl FailedProbeCounters {
+    pub async fn increment(&self, ip_address: IpAddr) {
+        let mut counters = self.counters.lock().await;
+        counters.entry(ip_address).or_default().inc();
+    }
+
+    async fn get(&self, ip_address: &IpAddr) -> u64 {
+        self.counters
+            .lock()
+            .await
+            .get(ip_address)
+            .map_or(0, Counter::get)
+    }
+}
+
+/// Per-IP counters for ticks where `RateLimiter` had no tokens left and the
+/// probe was skipped entirely, so that's visible after the fact instead of
+/// only ever showing up as a console `throttled` line.
+#[derive(Default)]
+pub struct ThrottledCounters {
+    counters: Mutex<HashMap<IpAddr, Counter>>,
+}
+
+impl ThrottledCounters {
+    pub async fn increment(&self, ip_address: IpAddr) {
+        let mut counters = self.counters.lock().await;
+        counters.entry(ip_address).or_default().inc();
+    }
+
+    async fn get(&self, ip_address: &IpAddr) -> u64 {
+        self.counters
+            .lock()
+            .await
+            .get(ip_address)
+            .map_or(0, Counter::get)
+    }
+}
+
+/// Renders the current state of `results`, `failed_probes` and
+/// `histogram_cache` in Prometheus text exposition format.
+pub async fn render_prometheus_metrics(
+    results: &IpResults,
+    ip_addresses: &[IpAddr],
+    failed_probes: &FailedProbeCounters,
+    throttled_probes: &ThrottledCounters,
+    histogram_cache: &HistogramCache,
+) -> String {
+    let mut output = String::new();
+
+    output.push_str(
+        "# HELP uptime_mtu_bytes Smallest MTU size seen in the most recent minute (0 if a probe failed).\n",
+    );
+    output.push_str("# TYPE uptime_mtu_bytes gauge\n");
+    for ip in ip_addresses {
+        if let Some((_, latest)) = results.get(ip).unwrap().lock().await.back() {
+            output.push_str(&format!("uptime_mtu_bytes{{ip=\"{ip}\"}} {}\n", latest.mtu));
+        }
+    }
+
+    output.push_str(
+        "# HELP uptime_latency_micros Largest probe latency in the most recent minute, in microseconds.\n",
+    );
+    output.push_str("# TYPE uptime_latency_micros gauge\n");
+    for ip in ip_addresses {
+        if let Some((_, latest)) = results.get(ip).unwrap().lock().await.back() {
+            output.push_str(&format!(
+                "uptime_latency_micros{{ip=\"{ip}\"}} {}\n",
+                latest.latency_micros
+            ));
+        }
+    }
+
+    output.push_str(
+        "# HELP uptime_failed_probes_total Total probes that found no connectivity.\n",
+    );
+    output.push_str("# TYPE uptime_failed_probes_total counter\n");
+    for ip in ip_addresses {
+        output.push_str(&format!(
+            "uptime_failed_probes_total{{ip=\"{ip}\"}} {}\n",
+            failed_probes.get(ip).await
+        ));
+    }
+
+    output.push_str(
+        "# HELP uptime_throttled_probes_total Total probe ticks skipped due to per-target rate limiting.\n",
+    );
+    output.push_str("# TYPE uptime_throttled_probes_total counter\n");
+    for ip in ip_addresses {
+        output.push_str(&format!(
+            "uptime_throttled_probes_total{{ip=\"{ip}\"}} {}\n",
+            throttled_probes.get(ip).await
+        ));
+    }
+
+    output.push_str(
+        "# HELP uptime_latency_micros_histogram Latency distribution since this agent started.\n",
+    );
+    output.push_str("# TYPE uptime_latency_micros_histogram histogram\n");
+    for ip in ip_addresses {
+        let (combined, sum_micros) = histogram_cache.snapshot(ip).await;
+        let total = combined.total();
+        for &bound in &LATENCY_BUCKET_BOUNDS_MICROS {
+            output.push_str(&format!(
+                "uptime_latency_micros_histogram_bucket{{ip=\"{ip}\",le=\"{bound}\"}} {}\n",
+                combined.count_le(bound)
+            ));
+        }
+        output.push_str(&format!(
+            "uptime_latency_micros_histogram_bucket{{ip=\"{ip}\",le=\"+Inf\"}} {total}\n"
+        ));
+        output.push_str(&format!(
+            "uptime_latency_micros_histogram_sum{{ip=\"{ip}\"}} {sum_micros}\n"
+        ));
+        output.push_str(&format!(
+            "uptime_latency_micros_histogram_count{{ip=\"{ip}\"}} {total}\n"
+        ));
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::PingResult;
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+
+    fn ip(last_octet: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last_octet])
+    }
+
+    #[tokio::test]
+    async fn histogram_cache_records_are_isolated_per_ip() {
+        let cache = HistogramCache::default();
+        let (probed, other) = (ip(1), ip(2));
+
+        cache.record(probed, 100).await;
+        cache.record(probed, 200).await;
+
+        let (probed_histogram, probed_sum) = cache.snapshot(&probed).await;
+        let (other_histogram, other_sum) = cache.snapshot(&other).await;
+
+        assert_eq!(probed_histogram.total(), 2);
+        assert_eq!(probed_sum, 300);
+        assert_eq!(other_histogram.total(), 0);
+        assert_eq!(other_sum, 0);
+    }
+
+    #[tokio::test]
+    async fn render_prometheus_metrics_includes_gauges_and_histogram_lines() {
+        let target = ip(1);
+        let ip_addresses = vec![target];
+
+        let mut results: IpResults = HashMap::new();
+        let timestamp = chrono::Local::now();
+        results.insert(
+            target,
+            Arc::new(Mutex::new(VecDeque::from([(timestamp, PingResult::from_sample(1400, 9_000))]))),
+        );
+
+        let failed_probes = FailedProbeCounters::default();
+        failed_probes.increment(target).await;
+
+        let throttled_probes = ThrottledCounters::default();
+        throttled_probes.increment(target).await;
+
+        let histogram_cache = HistogramCache::default();
+        histogram_cache.record(target, 9_000).await;
+
+        let body = render_prometheus_metrics(
+            &results,
+            &ip_addresses,
+            &failed_probes,
+            &throttled_probes,
+            &histogram_cache,
+        )
+        .await;
+
+        assert!(body.contains(&format!("uptime_mtu_bytes{{ip=\"{target}\"}} 1400")));
+        assert!(body.contains(&format!("uptime_latency_micros{{ip=\"{target}\"}} 9000")));
+        assert!(body.contains(&format!("uptime_failed_probes_total{{ip=\"{target}\"}} 1")));
+        assert!(body.contains(&format!("uptime_throttled_probes_total{{ip=\"{target}\"}} 1")));
+        assert!(body.contains(&format!("uptime_latency_micros_histogram_bucket{{ip=\"{target}\",le=\"+Inf\"}} 1")));
+        assert!(body.contains(&format!("uptime_latency_micros_histogram_sum{{ip=\"{target}\"}} 9000")));
+    }
+}
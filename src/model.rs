@@ -1,3 +1,4 @@
+use crate::histogram::LatencyHistogram;
 use chrono::{DateTime, Local};
 use std::collections::{HashMap, VecDeque};
 use std::net::IpAddr;
@@ -7,12 +8,51 @@ use tokio::sync::Mutex;
 pub enum MetricType {
     Mtu,
     Latency,
+    /// A latency percentile in `[0.0, 1.0]`, e.g. `0.99` for p99.
+    LatencyPercentile(f64),
 }
 
 #[derive(Clone)]
 pub struct PingResult {
     pub mtu: usize,
     pub latency_micros: u128,
+    pub latency_histogram: LatencyHistogram,
+}
+
+impl PingResult {
+    /// Builds a result for a single probe sample.
+    pub fn from_sample(mtu: usize, latency_micros: u128) -> Self {
+        let mut latency_histogram = LatencyHistogram::new();
+        latency_histogram.record(latency_micros);
+        Self {
+            mtu,
+            latency_micros,
+            latency_histogram,
+        }
+    }
+
+    /// Builds a sentinel result for a failed probe. The histogram is left
+    /// empty rather than recording `latency_micros`, so a failure doesn't
+    /// fabricate a latency sample that would distort percentiles; the
+    /// failure signal itself is tracked separately (see `FailedProbeCounters`).
+    pub fn failed(latency_micros: u128) -> Self {
+        Self {
+            mtu: 0,
+            latency_micros,
+            latency_histogram: LatencyHistogram::new(),
+        }
+    }
+
+    /// Merges another same-minute sample into this result: the MTU keeps the
+    /// worst (smallest) size, which is 0 once a failed sample is merged in,
+    /// latency keeps the worst (largest)
+    /// single sample, and the histograms are merged so percentiles reflect
+    /// every sample taken during the minute.
+    pub fn merge_sample(&mut self, other: &Self) {
+        self.mtu = self.mtu.min(other.mtu);
+        self.latency_micros = self.latency_micros.max(other.latency_micros);
+        self.latency_histogram.merge(&other.latency_histogram);
+    }
 }
 
 pub type TimedResult = (DateTime<Local>, PingResult);
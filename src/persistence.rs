@@ -0,0 +1,206 @@
+//! Disk persistence for probe history, so a restart doesn't lose the week
+//! of retained measurements the HTML report advertises.
+//!
+//! Each IP gets one CSV segment file per day under `base_dir`. Writes are
+//! buffered in memory and flushed by a background task rather than inline,
+//! and the actual disk work always happens on a blocking-pool thread.
+
+use crate::histogram::LatencyHistogram;
+use crate::model::{IpResults, PingResult, TimedResult};
+use chrono::{DateTime, Local, NaiveDate};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const RETENTION_DAYS: i64 = 7;
+
+/// Buffers `(IpAddr, TimedResult)` entries and flushes them to per-IP,
+/// per-day CSV segment files on demand.
+pub struct PersistenceLog {
+    base_dir: PathBuf,
+    pending: Mutex<Vec<(IpAddr, TimedResult)>>,
+}
+
+impl PersistenceLog {
+    pub fn new(base_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            base_dir: base_dir.into(),
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Queues a finalized sample for the next flush; does not touch disk.
+    pub async fn enqueue(&self, ip_address: IpAddr, entry: TimedResult) {
+        self.pending.lock().await.push((ip_address, entry));
+    }
+
+    /// Appends every queued sample to its per-day segment file. The actual
+    /// writes run on a blocking-pool thread so a slow disk can't stall the
+    /// tokio worker the probe loop also runs on.
+    pub async fn flush(&self) -> std::io::Result<()> {
+        let mut pending = self.pending.lock().await;
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let entries = std::mem::take(&mut *pending);
+        drop(pending);
+
+        let base_dir = self.base_dir.clone();
+        tokio::task::spawn_blocking(move || {
+            for (ip_address, (timestamp, ping_result)) in entries {
+                let path = segment_path(&base_dir, ip_address, timestamp.date_naive());
+                std::fs::create_dir_all(path.parent().unwrap())?;
+                let mut file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?;
+                writeln!(file, "{}", encode_row(timestamp, &ping_result))?;
+            }
+            Ok(())
+        })
+        .await
+        .expect("persistence flush task panicked")
+    }
+
+    /// Reloads the retained window of history for `ip_addresses` from disk,
+    /// for use at startup.
+    pub fn load(&self, ip_addresses: &[IpAddr]) -> IpResults {
+        let cutoff = (Local::now() - chrono::Duration::days(RETENTION_DAYS)).date_naive();
+        ip_addresses
+            .iter()
+            .map(|&ip| {
+                let mut entries: VecDeque<TimedResult> = VecDeque::new();
+                if let Ok(dir) = std::fs::read_dir(self.base_dir.join(ip.to_string())) {
+                    let mut segment_dates: Vec<NaiveDate> = dir
+                        .filter_map(Result::ok)
+                        .filter_map(|entry| parse_segment_date(&entry.path()))
+                        .filter(|date| *date >= cutoff)
+                        .collect();
+                    segment_dates.sort_unstable();
+                    for date in segment_dates {
+                        let Ok(contents) = std::fs::read_to_string(segment_path(&self.base_dir, ip, date))
+                        else {
+                            continue;
+                        };
+                        entries.extend(contents.lines().filter_map(decode_row));
+                    }
+                }
+                (ip, Arc::new(Mutex::new(entries)))
+            })
+            .collect()
+    }
+
+    /// Deletes segment files older than the retention window, run alongside
+    /// the in-memory pruning step. Runs on a blocking-pool thread for the
+    /// same reason [`Self::flush`] does.
+    pub async fn compact(&self, ip_addresses: &[IpAddr]) -> std::io::Result<()> {
+        let cutoff = (Local::now() - chrono::Duration::days(RETENTION_DAYS)).date_naive();
+        let base_dir = self.base_dir.clone();
+        let ip_addresses = ip_addresses.to_vec();
+        tokio::task::spawn_blocking(move || {
+            for ip_address in ip_addresses {
+                let Ok(read_dir) = std::fs::read_dir(base_dir.join(ip_address.to_string())) else {
+                    continue;
+                };
+                for entry in read_dir.filter_map(Result::ok) {
+                    if parse_segment_date(&entry.path()).is_some_and(|date| date < cutoff) {
+                        std::fs::remove_file(entry.path())?;
+                    }
+                }
+            }
+            Ok(())
+        })
+        .await
+        .expect("persistence compact task panicked")
+    }
+}
+
+fn segment_path(base_dir: &Path, ip_address: IpAddr, date: NaiveDate) -> PathBuf {
+    base_dir.join(ip_address.to_string()).join(format!("{date}.csv"))
+}
+
+fn encode_row(timestamp: DateTime<Local>, ping_result: &PingResult) -> String {
+    let histogram = ping_result
+        .latency_histogram
+        .nonzero_buckets()
+        .map(|(index, count)| format!("{index}:{count}"))
+        .collect::<Vec<_>>()
+        .join(";");
+    format!(
+        "{},{},{},{histogram}",
+        timestamp.to_rfc3339(),
+        ping_result.mtu,
+        ping_result.latency_micros
+    )
+}
+
+fn decode_row(line: &str) -> Option<TimedResult> {
+    let mut fields = line.splitn(4, ',');
+    let timestamp = DateTime::parse_from_rfc3339(fields.next()?)
+        .ok()?
+        .with_timezone(&Local);
+    let mtu = fields.next()?.parse().ok()?;
+    let latency_micros = fields.next()?.parse().ok()?;
+
+    let mut ping_result = PingResult::from_sample(mtu, latency_micros);
+    ping_result.latency_histogram = LatencyHistogram::new();
+    for bucket in fields.next().unwrap_or("").split(';').filter(|s| !s.is_empty()) {
+        let (index, count) = bucket.split_once(':')?;
+        ping_result
+            .latency_histogram
+            .set_bucket(index.parse().ok()?, count.parse().ok()?);
+    }
+
+    Some((timestamp, ping_result))
+}
+
+fn parse_segment_date(path: &Path) -> Option<NaiveDate> {
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn encode_then_decode_row_round_trips_mtu_and_latency() {
+        let timestamp = Local.with_ymd_and_hms(2026, 1, 2, 3, 4, 0).unwrap();
+        let ping_result = PingResult::from_sample(1400, 12_345);
+
+        let encoded = encode_row(timestamp, &ping_result);
+        let (decoded_timestamp, decoded_result) = decode_row(&encoded).expect("row should decode");
+
+        assert_eq!(decoded_timestamp, timestamp);
+        assert_eq!(decoded_result.mtu, 1400);
+        assert_eq!(decoded_result.latency_micros, 12_345);
+    }
+
+    #[test]
+    fn encode_then_decode_row_round_trips_the_sparse_histogram() {
+        let timestamp = Local.with_ymd_and_hms(2026, 1, 2, 3, 4, 0).unwrap();
+        let mut ping_result = PingResult::from_sample(1400, 50);
+        ping_result.latency_histogram.record(50);
+        ping_result.latency_histogram.record(2_000_000);
+
+        let encoded = encode_row(timestamp, &ping_result);
+        let (_, decoded_result) = decode_row(&encoded).expect("row should decode");
+
+        assert_eq!(
+            decoded_result.latency_histogram.total(),
+            ping_result.latency_histogram.total()
+        );
+        assert_eq!(
+            decoded_result.latency_histogram.percentile(0.99),
+            ping_result.latency_histogram.percentile(0.99)
+        );
+    }
+
+    #[test]
+    fn decode_row_rejects_malformed_input() {
+        assert!(decode_row("not,a,valid,row").is_none());
+    }
+}
@@ -0,0 +1,99 @@
+//! Per-target token-bucket pacing. Caps each host to a sustainable probe
+//! rate while still allowing a short burst, independently of every other
+//! configured target.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Instant;
+use tokio::sync::Mutex;
+
+struct Bucket {
+    tokens: u64,
+    last_refill: Instant,
+}
+
+/// A token bucket per `IpAddr`. Tokens are denominated in nanoseconds: a
+/// probe costs `1_000_000_000 / packets_per_second` tokens, and the bucket
+/// refills by the number of nanoseconds elapsed since it was last touched,
+/// capped at `burst_size` probes' worth of tokens.
+pub struct RateLimiter {
+    packet_cost_nanos: u64,
+    max_tokens: u64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(packets_per_second: u64, burst_size: u64) -> Self {
+        let packet_cost_nanos = 1_000_000_000 / packets_per_second.max(1);
+        Self {
+            packet_cost_nanos,
+            max_tokens: packet_cost_nanos * burst_size.max(1),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Refills `ip_address`'s bucket for the time elapsed since it was last
+    /// consulted, then deducts the cost of one probe if enough tokens are
+    /// available. Returns whether the probe may proceed.
+    pub async fn try_acquire(&self, ip_address: IpAddr) -> bool {
+        let mut buckets = self.buckets.lock().await;
+        let now = Instant::now();
+        let bucket = buckets.entry(ip_address).or_insert_with(|| Bucket {
+            tokens: self.max_tokens,
+            last_refill: now,
+        });
+
+        let elapsed_nanos =
+            u64::try_from(now.duration_since(bucket.last_refill).as_nanos()).unwrap_or(u64::MAX);
+        bucket.tokens = bucket.tokens.saturating_add(elapsed_nanos).min(self.max_tokens);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= self.packet_cost_nanos {
+            bucket.tokens -= self.packet_cost_nanos;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip() -> IpAddr {
+        "10.0.0.1".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn burst_allows_up_to_burst_size_back_to_back() {
+        let limiter = RateLimiter::new(10, 3);
+
+        assert!(limiter.try_acquire(ip()).await);
+        assert!(limiter.try_acquire(ip()).await);
+        assert!(limiter.try_acquire(ip()).await);
+        assert!(!limiter.try_acquire(ip()).await);
+    }
+
+    #[tokio::test]
+    async fn refill_eventually_allows_another_probe() {
+        let limiter = RateLimiter::new(1000, 1);
+
+        assert!(limiter.try_acquire(ip()).await);
+        assert!(!limiter.try_acquire(ip()).await);
+
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+        assert!(limiter.try_acquire(ip()).await);
+    }
+
+    #[tokio::test]
+    async fn each_ip_gets_its_own_independent_bucket() {
+        let limiter = RateLimiter::new(10, 1);
+        let other_ip: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!(limiter.try_acquire(ip()).await);
+        assert!(!limiter.try_acquire(ip()).await);
+        assert!(limiter.try_acquire(other_ip).await);
+    }
+}